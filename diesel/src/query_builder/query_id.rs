@@ -1,5 +1,86 @@
 use super::QueryFragment;
 use std::any::{Any, TypeId};
+use std::hash::Hasher;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A [FNV-1a] hasher used to derive stable `u64` identities for queries which
+/// cannot be told apart by their type alone.
+///
+/// Unlike the hashers provided by the standard library, FNV-1a yields the same
+/// value regardless of the process or platform it runs in. That stability is
+/// what lets us key the prepared statement cache on the generated SQL of a
+/// dynamic query rather than on a `TypeId`.
+///
+/// [FNV-1a]: https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function
+pub(crate) struct FnvHasher(u64);
+
+impl FnvHasher {
+    pub(crate) fn new() -> Self {
+        FnvHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher::new()
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// Identifies an entry in the prepared statement cache.
+///
+/// Most queries are uniquely identified by their type, in which case the
+/// [`TypeId`] of their [`QueryId`] is used (the `Type` variant). Queries whose
+/// SQL is only known at runtime -- most notably boxed queries built with
+/// `into_boxed` -- have no static type to keep them apart, so they fall back to
+/// a `Dynamic` identity computed by hashing the generated SQL template together
+/// with the SQL types of their bind parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatementCacheKey {
+    /// The query is identified by the type id of its `QueryId`.
+    Type(TypeId),
+    /// The query is identified by a hash of its SQL template and bind types.
+    Dynamic(u64),
+}
+
+impl StatementCacheKey {
+    /// Builds a `Dynamic` key from the placeholder SQL of a query and the
+    /// ordered SQL types of its bind parameters.
+    ///
+    /// `sql_template` is the generated SQL *before* any values are bound (still
+    /// containing the bind placeholders), and `bind_types` lists the SQL type
+    /// identifiers (OIDs on PostgreSQL) of the binds in the order they appear.
+    /// Two queries share a key exactly when both of these agree, so binds with
+    /// differing values reuse a statement while binds with differing SQL types
+    /// do not.
+    ///
+    /// The SQL byte length is hashed ahead of the SQL itself so the boundary
+    /// between the template and the bind types is unambiguous -- SQL text can
+    /// never absorb bytes that belong to the following OIDs.
+    pub fn dynamic(sql_template: &str, bind_types: &[u32]) -> Self {
+        let mut hasher = FnvHasher::new();
+        hasher.write(&(sql_template.len() as u64).to_le_bytes());
+        hasher.write(sql_template.as_bytes());
+        for oid in bind_types {
+            hasher.write(&oid.to_le_bytes());
+        }
+        StatementCacheKey::Dynamic(hasher.finish())
+    }
+}
 
 /// Uniquely identifies queries by their type for the purpose of prepared
 /// statement caching.
@@ -112,6 +193,51 @@ impl<DB> QueryId for QueryFragment<DB> {
     const HAS_STATIC_QUERY_ID: bool = false;
 }
 
+/// Returns the cache identity a query would use in the prepared statement
+/// cache, if it has a static one.
+///
+/// This is the public counterpart to [`QueryId::query_id`]: it wraps the
+/// type-based identity in a [`StatementCacheKey::Type`]. Queries without a
+/// static query id -- such as boxed queries built with `into_boxed` -- return
+/// `None`, since their identity is only known once their SQL has been
+/// generated.
+///
+/// Applications can use this to log the distinct prepared statements their code
+/// produces, build cache-hit metrics, or deduplicate a batch of queries before
+/// execution, and to spot accidental cache-busting -- a query whose type varies
+/// per call always yields `None`.
+///
+/// To observe the identity of a dynamic query as well, render its SQL and pass
+/// it to [`query_cache_key_for`], which returns a `Dynamic` key instead of
+/// `None`.
+pub fn query_cache_key<Q: QueryId>(_query: &Q) -> Option<StatementCacheKey> {
+    Q::query_id().map(StatementCacheKey::Type)
+}
+
+/// Computes the statement-cache identity for a query whose SQL has already been
+/// rendered.
+///
+/// Queries with a static query id are keyed by [`StatementCacheKey::Type`];
+/// queries without one -- boxed queries built with `into_boxed` -- fall back to
+/// a [`StatementCacheKey::Dynamic`] hash of their generated SQL template and
+/// bind SQL types. This function only computes the key; it does not render
+/// the query's SQL, collect its bind types, or look up or insert anything in a
+/// statement cache.
+///
+/// `sql_template` is the generated SQL before any values are bound (still
+/// holding the bind placeholders) and `bind_types` lists the SQL type
+/// identifiers of the binds in order; see [`StatementCacheKey::dynamic`].
+pub fn query_cache_key_for<Q: QueryId>(
+    query: &Q,
+    sql_template: &str,
+    bind_types: &[u32],
+) -> StatementCacheKey {
+    match query_cache_key(query) {
+        Some(key) => key,
+        None => StatementCacheKey::dynamic(sql_template, bind_types),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::any::TypeId;
@@ -130,6 +256,34 @@ mod tests {
         T::query_id()
     }
 
+    struct DynamicQuery;
+
+    impl QueryId for DynamicQuery {
+        type QueryId = ();
+
+        const HAS_STATIC_QUERY_ID: bool = false;
+    }
+
+    #[test]
+    fn dynamic_queries_get_a_dynamic_cache_key() {
+        use super::{query_cache_key, query_cache_key_for, StatementCacheKey};
+        assert!(query_cache_key(&DynamicQuery).is_none());
+        match query_cache_key_for(&DynamicQuery, "SELECT * FROM users WHERE id = $1", &[23]) {
+            StatementCacheKey::Dynamic(_) => {}
+            other => panic!("expected a dynamic cache key, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn static_queries_keep_a_type_cache_key_when_sql_is_supplied() {
+        use super::{query_cache_key_for, StatementCacheKey};
+        use self::users::dsl::*;
+        match query_cache_key_for(&users.select(name), "SELECT name FROM users", &[]) {
+            StatementCacheKey::Type(_) => {}
+            other => panic!("expected a type cache key, got {:?}", other),
+        }
+    }
+
     #[test]
     fn queries_with_no_dynamic_elements_have_a_static_id() {
         use self::users::dsl::*;
@@ -160,4 +314,44 @@ mod tests {
         use pg::Pg;
         assert!(query_id(users::table.into_boxed::<Pg>()).is_none());
     }
+
+    #[test]
+    fn query_cache_key_is_type_based_for_static_queries() {
+        use super::{query_cache_key, StatementCacheKey};
+        use self::users::dsl::*;
+        match query_cache_key(&users.select(name)) {
+            Some(StatementCacheKey::Type(_)) => {}
+            other => panic!("expected a type-based cache key, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn query_cache_key_for_surfaces_distinct_dynamic_identities() {
+        use super::{query_cache_key_for, StatementCacheKey};
+        let a = query_cache_key_for(&DynamicQuery, "SELECT * FROM users WHERE id = $1", &[23]);
+        let b = query_cache_key_for(&DynamicQuery, "SELECT * FROM users WHERE name = $1", &[25]);
+        assert_ne!(a, b);
+        assert!(match a {
+            StatementCacheKey::Dynamic(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn dynamic_keys_are_stable_for_identical_sql_and_bind_types() {
+        use super::StatementCacheKey;
+        let id1 = StatementCacheKey::dynamic("SELECT * FROM users WHERE name = $1", &[25]);
+        let id2 = StatementCacheKey::dynamic("SELECT * FROM users WHERE name = $1", &[25]);
+        assert_eq!(id1, id2);
+    }
+
+    #[test]
+    fn dynamic_keys_differ_on_sql_or_bind_types() {
+        use super::StatementCacheKey;
+        let base = StatementCacheKey::dynamic("SELECT * FROM users WHERE name = $1", &[25]);
+        let other_sql = StatementCacheKey::dynamic("SELECT * FROM users WHERE id = $1", &[25]);
+        let other_type = StatementCacheKey::dynamic("SELECT * FROM users WHERE name = $1", &[23]);
+        assert_ne!(base, other_sql);
+        assert_ne!(base, other_type);
+    }
 }